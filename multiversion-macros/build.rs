@@ -1,18 +1,33 @@
 fn main() {
+    let rustflags = std::env::var("CARGO_ENCODED_RUSTFLAGS").unwrap();
+    let target_features: Vec<String> = rustflags
+        .split('\x1f')
+        .filter_map(|flag| {
+            flag.strip_prefix("target-feature=")
+                .or_else(|| flag.strip_prefix("-Ctarget-feature="))
+        })
+        .flat_map(|features| features.split(','))
+        .filter_map(|f| f.strip_prefix('+'))
+        .map(String::from)
+        .collect();
+
     // retpolines are not yet recognized by rust as a regular target feature.
     // We can't detect them with `cfg(target_feature = "retpoline")`, but we can detect them in
     // rustflags, since they shouldn't be the default for any target.
-    let rustflags = std::env::var("CARGO_ENCODED_RUSTFLAGS").unwrap();
-    let retpolines_enabled = rustflags.split('\x1f').any(|flag| {
-        flag.strip_prefix("target-feature=")
-            .or_else(|| flag.strip_prefix("-Ctarget-feature="))
-            .map(|features| features.split(',').any(|f| f.starts_with("+retpoline")))
-            .unwrap_or(false)
-    });
-
+    let retpolines_enabled = target_features.iter().any(|f| f.starts_with("retpoline"));
     if retpolines_enabled {
         println!("cargo::rustc-cfg=retpoline")
     }
     println!("cargo::rustc-check-cfg=cfg(retpoline)");
+
+    // `cfg!` only accepts a literal key/value, but the dispatcher needs to ask "is this feature
+    // (a runtime string) statically enabled?" for an arbitrary target's feature list. Bake the
+    // same list into the macro binary as a comma-separated constant it can split and search at
+    // expansion time.
+    println!(
+        "cargo::rustc-env=MULTIVERSION_STATIC_FEATURES={}",
+        target_features.join(",")
+    );
+
     println!("cargo::rerun-if-changed=build.rs");
 }
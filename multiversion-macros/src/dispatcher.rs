@@ -0,0 +1,376 @@
+use crate::target::Target;
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote, ToTokens};
+use syn::{Attribute, FnArg, Ident, ItemFn, Pat};
+
+/// How a multiversioned function selects which clone to call at runtime.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DispatchMethod {
+    /// Used when no `dispatcher` is given; generates the same per-call detection chain as `Direct`.
+    Default,
+    /// Resolve purely from what's statically known about the build; never probe at runtime.
+    Static,
+    /// Re-run feature detection on every call; no caching.
+    Direct,
+    /// Resolve the best clone once, cache it in a shared atomic, and use it for every later call.
+    Indirect,
+    /// Resolve the best clone once per thread, cached in a thread-local cell with no atomics.
+    CachedLocal,
+}
+
+/// Generates the specialized clones of a `#[multiversion]` function plus the dispatch logic that
+/// picks between them at runtime.
+pub(crate) struct Dispatcher {
+    pub targets: Vec<Target>,
+    pub func: ItemFn,
+    pub inner_attrs: Vec<Attribute>,
+    pub dispatcher: DispatchMethod,
+}
+
+impl Dispatcher {
+    fn clone_ident(&self, index: usize) -> Ident {
+        format_ident!("__{}_{}", self.func.sig.ident, index)
+    }
+
+    /// The always-available, unspecialized fallback: the original function body under a private
+    /// name, called when no clone's feature requirements are met.
+    fn generic_ident(&self) -> Ident {
+        format_ident!("__{}_generic", self.func.sig.ident)
+    }
+
+    fn resolve_ident(&self) -> Ident {
+        format_ident!("__{}_resolve", self.func.sig.ident)
+    }
+
+    fn cache_ident(&self) -> Ident {
+        format_ident!("__{}_CACHE", self.func.sig.ident.to_string().to_uppercase())
+    }
+
+    /// All declared targets, in the priority order they were declared (highest tier first).
+    ///
+    /// Predicate gating is not applied here: whether a predicate holds can only be known for the
+    /// real downstream crate and target, so it's deferred to the `#[cfg(..)]` attached to each
+    /// clone by [`Self::cfg_predicate`] rather than filtering the list at macro-expansion time.
+    fn all_targets(&self) -> Vec<&Target> {
+        self.targets.iter().collect()
+    }
+
+    fn arch_cfg(target: &Target) -> TokenStream {
+        match target.architecture() {
+            Some(arch) => quote! { target_arch = #arch },
+            None => quote! { any() },
+        }
+    }
+
+    /// The full `#[cfg(..)]` predicate a clone (and the runtime check that guards calling it)
+    /// must satisfy to exist at all: its architecture, further narrowed by the target's own
+    /// predicate if it carries one. Evaluated by rustc for the real downstream crate and target,
+    /// same as `arch_cfg` already was.
+    fn cfg_predicate(target: &Target) -> TokenStream {
+        let arch_cfg = Self::arch_cfg(target);
+        match target.predicate_cfg() {
+            Some(predicate_cfg) => quote! { all(#arch_cfg, #predicate_cfg) },
+            None => arch_cfg,
+        }
+    }
+
+    fn feature_detect_expr(target: &Target) -> TokenStream {
+        let arch = target.architecture().unwrap_or_default();
+        let detect_macro = match arch {
+            "x86" | "x86_64" => quote! { is_x86_feature_detected },
+            "arm" => quote! { is_arm_feature_detected },
+            "aarch64" => quote! { is_aarch64_feature_detected },
+            "mips" => quote! { is_mips_feature_detected },
+            "mips64" => quote! { is_mips64_feature_detected },
+            "powerpc" => quote! { is_powerpc_feature_detected },
+            "powerpc64" => quote! { is_powerpc64_feature_detected },
+            _ => quote! { is_x86_feature_detected },
+        };
+        let checks = target
+            .features()
+            .iter()
+            .map(|feature| quote! { #detect_macro!(#feature) });
+        quote! { #(#checks)&&* }
+    }
+
+    fn target_feature_attr(target: &Target) -> TokenStream {
+        let features = target.features().join(",");
+        quote! { #[target_feature(enable = #features)] }
+    }
+
+    /// Whether `feature` is guaranteed to be enabled for every function in this build, either
+    /// because rustc reports it directly (e.g. it was folded in from `-C target-cpu`) or because
+    /// our build script found an explicit `-C target-feature=+X` in `RUSTFLAGS`.
+    ///
+    /// `cfg!` only accepts a literal key and value, so the finite set of features we ever put in
+    /// one of the `simd*` presets is spelled out explicitly; anything outside that set falls
+    /// through to the `MULTIVERSION_STATIC_FEATURES` list baked in by `build.rs`.
+    fn feature_is_static(feature: &str) -> bool {
+        macro_rules! checked_by_rustc {
+            ($($literal:literal),* $(,)?) => {
+                match feature {
+                    $($literal => cfg!(target_feature = $literal),)*
+                    _ => false,
+                }
+            };
+        }
+        let reported_by_rustc = checked_by_rustc!(
+            "avx2", "avx512f", "avx512bw", "avx512cd", "avx512dq", "avx512vl", "fma", "sse2",
+            "sse4.2", "neon", "msa", "vsx", "altivec",
+        );
+        reported_by_rustc
+            || option_env!("MULTIVERSION_STATIC_FEATURES")
+                .map(|features| features.split(',').any(|f| f == feature))
+                .unwrap_or(false)
+    }
+
+    /// Whether `target`'s architecture is the one this very macro invocation is being compiled
+    /// for. A feature being statically enabled is meaningless if it was measured against the
+    /// wrong architecture (e.g. a 32-bit `x86` target evaluated while compiling for `x86_64`),
+    /// since that target's clone won't even exist in the output.
+    fn arch_matches_host(target: &Target) -> bool {
+        match target.architecture() {
+            Some(arch) => {
+                macro_rules! checked_by_rustc {
+                    ($($literal:literal),* $(,)?) => {
+                        match arch {
+                            $($literal => cfg!(target_arch = $literal),)*
+                            _ => false,
+                        }
+                    };
+                }
+                checked_by_rustc!(
+                    "x86", "x86_64", "arm", "aarch64", "mips", "mips64", "powerpc", "powerpc64",
+                )
+            }
+            None => true,
+        }
+    }
+
+    /// Whether every feature this target requires is statically guaranteed, meaning runtime
+    /// detection for it can never fail and a clone further down the priority list (which this one
+    /// would always beat) can never be selected.
+    ///
+    /// A target carrying a predicate is never statically guaranteed, regardless of its features:
+    /// whether the predicate holds isn't known until the real downstream build, so treating it as
+    /// guaranteed would make it the unconditional dispatch tail even on a build where its `#[cfg]`
+    /// predicate is false and its clone doesn't exist at all.
+    fn is_statically_guaranteed(target: &Target) -> bool {
+        target.predicate_cfg().is_none()
+            && target.has_features_specified()
+            && Self::arch_matches_host(target)
+            && target.features().iter().all(|f| Self::feature_is_static(f))
+    }
+
+    /// Idents of the function's parameters, in order, used to forward arguments from the
+    /// dispatch body to whichever clone is selected.
+    fn arg_idents(&self) -> Vec<Ident> {
+        self.func
+            .sig
+            .inputs
+            .iter()
+            .filter_map(|arg| match arg {
+                FnArg::Typed(pat_type) => match &*pat_type.pat {
+                    Pat::Ident(pat_ident) => Some(pat_ident.ident.clone()),
+                    _ => None,
+                },
+                FnArg::Receiver(_) => None,
+            })
+            .collect()
+    }
+
+    /// The type of an unsafe function pointer matching this function's signature, used to cache a
+    /// resolved clone in `Indirect`/`CachedLocal` dispatch.
+    fn fn_ptr_type(&self) -> TokenStream {
+        let arg_types = self.func.sig.inputs.iter().filter_map(|arg| match arg {
+            FnArg::Typed(pat_type) => Some(&*pat_type.ty),
+            FnArg::Receiver(_) => None,
+        });
+        let output = &self.func.sig.output;
+        quote! { unsafe fn(#(#arg_types),*) #output }
+    }
+
+    /// Build one specialized clone of the function body, gated to the target's architecture and
+    /// annotated with the features it's allowed to assume. Clones are `unsafe fn`, since calling
+    /// one without having actually checked for its features is undefined behavior.
+    fn make_clone(&self, index: usize, target: &Target) -> TokenStream {
+        let ident = self.clone_ident(index);
+        let cfg_predicate = Self::cfg_predicate(target);
+        let target_feature_attr = Self::target_feature_attr(target);
+        let inner_attrs = &self.inner_attrs;
+        let mut sig = self.func.sig.clone();
+        sig.ident = ident;
+        sig.unsafety = Some(Default::default());
+        let block = &self.func.block;
+        quote! {
+            #[cfg(#cfg_predicate)]
+            #target_feature_attr
+            #(#inner_attrs)*
+            #sig #block
+        }
+    }
+
+    /// The unspecialized fallback function, always available regardless of target or features.
+    fn make_generic_fn(&self) -> TokenStream {
+        let ident = self.generic_ident();
+        let inner_attrs = &self.inner_attrs;
+        let mut sig = self.func.sig.clone();
+        sig.ident = ident;
+        let block = &self.func.block;
+        quote! {
+            #(#inner_attrs)*
+            #sig #block
+        }
+    }
+
+    /// Build a chain of feature checks in priority order, each returning either a direct call to
+    /// its clone (`as_pointer = false`) or the clone's function pointer (`as_pointer = true`),
+    /// falling back to the generic function once nothing above it matches.
+    ///
+    /// `static_tail`, if set, is the index of the first target whose whole feature set is
+    /// statically guaranteed (see [`Self::is_statically_guaranteed`]): everything at or below it
+    /// is collapsed into a single unconditional tail, since runtime detection there could never
+    /// come out differently and nothing after it could ever win.
+    ///
+    /// `skip_runtime_checks` drops the rest of the chain instead of emitting it, for
+    /// [`DispatchMethod::Static`]: only the statically-guaranteed tail (or the generic fallback,
+    /// if there is none) is ever reachable, so nothing in this function probes at runtime.
+    fn make_chain(
+        &self,
+        targets: &[&Target],
+        static_tail: Option<usize>,
+        as_pointer: bool,
+        skip_runtime_checks: bool,
+    ) -> TokenStream {
+        let args = self.arg_idents();
+        let generic = self.generic_ident();
+        let ptr_ty = self.fn_ptr_type();
+
+        let resolved = |ident: &Ident| {
+            if as_pointer {
+                quote! { #ident as #ptr_ty }
+            } else {
+                quote! { unsafe { #ident(#(#args),*) } }
+            }
+        };
+
+        let mut body = match static_tail {
+            Some(index) => resolved(&self.clone_ident(index)),
+            None => resolved(&generic),
+        };
+
+        let runtime_checked = if skip_runtime_checks {
+            0
+        } else {
+            static_tail.unwrap_or(targets.len())
+        };
+        for (index, target) in targets.iter().enumerate().take(runtime_checked).rev() {
+            let ident = self.clone_ident(index);
+            let cfg_predicate = Self::cfg_predicate(target);
+            let detect = Self::feature_detect_expr(target);
+            let call = resolved(&ident);
+            body = quote! {
+                #[cfg(#cfg_predicate)]
+                {
+                    if #detect {
+                        return #call;
+                    }
+                }
+                #body
+            };
+        }
+        body
+    }
+
+    /// The cached dispatch strategies (`Indirect`/`CachedLocal`) share everything but the cache
+    /// storage itself: resolve the best clone into a function pointer once, then read it back
+    /// through whatever cache `cache` builds around `resolve_ident`.
+    fn make_cached_dispatch(
+        &self,
+        targets: &[&Target],
+        static_tail: Option<usize>,
+        cache: impl FnOnce(&Ident, &TokenStream) -> TokenStream,
+    ) -> TokenStream {
+        let resolve_ident = self.resolve_ident();
+        let ptr_ty = self.fn_ptr_type();
+        let resolve_body = self.make_chain(targets, static_tail, true, false);
+        let args = self.arg_idents();
+        let cached_fn = cache(&resolve_ident, &ptr_ty);
+        quote! {
+            #[inline(never)]
+            fn #resolve_ident() -> #ptr_ty {
+                #resolve_body
+            }
+            #cached_fn
+            unsafe { __multiversion_dispatch(#(#args),*) }
+        }
+    }
+}
+
+impl ToTokens for Dispatcher {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let targets = self.all_targets();
+
+        // No targets were declared at all: emit the function unmodified.
+        if targets.is_empty() {
+            self.func.to_tokens(tokens);
+            return;
+        }
+
+        // The first target (if any) whose entire feature set is statically guaranteed always
+        // wins over every target after it, so those lower-tier clones would be dead code.
+        let static_tail = targets.iter().position(|t| Self::is_statically_guaranteed(t));
+        let live = static_tail.map_or(targets.len(), |index| index + 1);
+
+        let clones = targets[..live]
+            .iter()
+            .enumerate()
+            .map(|(index, target)| self.make_clone(index, target));
+        let generic_fn = self.make_generic_fn();
+
+        let body = match self.dispatcher {
+            DispatchMethod::Default | DispatchMethod::Direct => {
+                self.make_chain(&targets, static_tail, false, false)
+            }
+            DispatchMethod::Static => self.make_chain(&targets, static_tail, false, true),
+            DispatchMethod::Indirect => {
+                let cache_ident = self.cache_ident();
+                self.make_cached_dispatch(&targets, static_tail, |resolve_ident, ptr_ty| {
+                    quote! {
+                        static #cache_ident: ::core::sync::atomic::AtomicPtr<()> =
+                            ::core::sync::atomic::AtomicPtr::new(::core::ptr::null_mut());
+                        let __cached = #cache_ident.load(::core::sync::atomic::Ordering::Relaxed);
+                        let __multiversion_dispatch: #ptr_ty = if __cached.is_null() {
+                            let __resolved = #resolve_ident();
+                            #cache_ident.store(__resolved as *mut (), ::core::sync::atomic::Ordering::Relaxed);
+                            __resolved
+                        } else {
+                            unsafe { ::core::mem::transmute(__cached) }
+                        };
+                    }
+                })
+            }
+            DispatchMethod::CachedLocal => {
+                self.make_cached_dispatch(&targets, static_tail, |resolve_ident, ptr_ty| {
+                    quote! {
+                        ::std::thread_local! {
+                            static __MULTIVERSION_CACHE: ::std::cell::OnceCell<#ptr_ty> =
+                                const { ::std::cell::OnceCell::new() };
+                        }
+                        let __multiversion_dispatch: #ptr_ty =
+                            __MULTIVERSION_CACHE.with(|cell| *cell.get_or_init(#resolve_ident));
+                    }
+                })
+            }
+        };
+
+        let mut func = self.func.clone();
+        func.block = syn::parse_quote! {{ #body }};
+
+        tokens.extend(quote! {
+            #(#clones)*
+            #generic_fn
+            #func
+        });
+    }
+}
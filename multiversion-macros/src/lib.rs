@@ -0,0 +1,17 @@
+//! Proc-macro implementation crate for `multiversion`. Not meant to be used directly; see the
+//! `multiversion` crate for the public API and documentation.
+
+mod dispatcher;
+mod multiversion;
+mod target;
+
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, ItemFn};
+
+#[proc_macro_attribute]
+pub fn multiversion(attr: TokenStream, input: TokenStream) -> TokenStream {
+    let func = parse_macro_input!(input as ItemFn);
+    multiversion::make_multiversioned_fn(attr.into(), func)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
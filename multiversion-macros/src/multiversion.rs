@@ -7,25 +7,64 @@ use syn::{
     Attribute, Error, ItemFn, LitStr, Meta, ReturnType, Type,
 };
 
-// Default set of targets that are selected when `targets = "simd"` is specified.
-static DEFAULT_TARGETS: &[&str] = &[
-    // "x86_64+avx512f+avx512bw+avx512cd+avx512dq+avx512vl",
+// Targets selected when `targets = "simd"` is specified: a conservative set of widely-available
+// tiers for the architectures most users build for.
+static SIMD_TARGETS: &[&str] = &[
     "x86_64+avx2+fma",
     "x86_64+sse4.2",
-    // "x86+avx512f+avx512bw+avx512cd+avx512dq+avx512vl",
     "x86+avx2+fma",
     "x86+sse4.2",
     "x86+sse2",
     "aarch64+neon",
-    // "arm+neon",
-    // "mips+msa",
-    // "mips64+msa",
-    // "powerpc+vsx",
-    // "powerpc+altivec",
-    // "powerpc64+vsx",
-    // "powerpc64+altivec",
 ];
 
+// Targets selected when `targets = "simd-avx512"` is specified: `simd`, plus an AVX-512 tier
+// ahead of AVX2 on `x86`/`x86_64` for users building on a toolchain/hardware combination that
+// supports it.
+static SIMD_AVX512_TARGETS: &[&str] = &[
+    "x86_64+avx512f+avx512bw+avx512cd+avx512dq+avx512vl",
+    "x86_64+avx2+fma",
+    "x86_64+sse4.2",
+    "x86+avx512f+avx512bw+avx512cd+avx512dq+avx512vl",
+    "x86+avx2+fma",
+    "x86+sse4.2",
+    "x86+sse2",
+    "aarch64+neon",
+];
+
+// Targets selected when `targets = "simd-all"` is specified: `simd`, plus tiers for every other
+// architecture `multiversion` knows clone syntax for.
+static SIMD_ALL_TARGETS: &[&str] = &[
+    "x86_64+avx2+fma",
+    "x86_64+sse4.2",
+    "x86+avx2+fma",
+    "x86+sse4.2",
+    "x86+sse2",
+    "aarch64+neon",
+    "arm+neon",
+    "mips+msa",
+    "mips64+msa",
+    "powerpc+vsx",
+    "powerpc+altivec",
+    "powerpc64+vsx",
+    "powerpc64+altivec",
+];
+
+// Named presets selectable via `targets = "<name>"`, each a priority-ordered list of targets
+// (highest feature tier first) handed to `Target::parse`.
+static PRESETS: &[(&str, &[&str])] = &[
+    ("simd", SIMD_TARGETS),
+    ("simd-avx512", SIMD_AVX512_TARGETS),
+    ("simd-all", SIMD_ALL_TARGETS),
+];
+
+fn preset_targets(name: &str) -> Option<&'static [&'static str]> {
+    PRESETS
+        .iter()
+        .find(|(preset_name, _)| *preset_name == name)
+        .map(|(_, targets)| *targets)
+}
+
 fn parse_targets(
     meta: syn::meta::ParseNestedMeta,
     targets: &mut Option<Vec<Target>>,
@@ -37,6 +76,10 @@ fn parse_targets(
     if meta.input.peek(token::Paren) {
         let content;
         parenthesized!(content in meta.input);
+        // Each entry may carry a trailing `if cfg(..)` predicate (see `Target`'s `Parse` impl).
+        // It isn't evaluated by the macro: it becomes a `#[cfg(..)]` on the target's clone and
+        // dispatch check, so rustc drops targets whose predicate doesn't hold, and if none are
+        // left the dispatch falls back to the generic body.
         *targets = Some(
             Punctuated::<Target, token::Comma>::parse_terminated(&content)?
                 .into_iter()
@@ -47,16 +90,21 @@ fn parse_targets(
         let value = meta.value()?;
         let s: LitStr = value.parse()?;
 
-        if s.value().as_str() == "simd" {
+        if let Some(preset) = preset_targets(s.value().as_str()) {
             *targets = Some(
-                DEFAULT_TARGETS
+                preset
                     .iter()
                     .map(|x| Target::parse(&LitStr::new(x, meta.path.span())).unwrap())
                     .collect(),
             );
             Ok(())
         } else {
-            Err(meta.error("expected a list of features or \"simd\""))
+            let names = PRESETS
+                .iter()
+                .map(|(name, _)| format!("\"{name}\""))
+                .collect::<Vec<_>>()
+                .join(", ");
+            Err(meta.error(format!("expected a list of features or one of {names}")))
         }
     }
 }
@@ -94,7 +142,12 @@ fn parse_dispatcher(
         "static" => DispatchMethod::Static,
         "direct" => DispatchMethod::Direct,
         "indirect" => DispatchMethod::Indirect,
-        _ => return Err(meta.error("expected `default`, `static`, `direct`, or `indirect`")),
+        "cached-local" => DispatchMethod::CachedLocal,
+        _ => {
+            return Err(meta.error(
+                "expected `default`, `static`, `direct`, `indirect`, or `cached-local`",
+            ))
+        }
     });
     Ok(())
 }
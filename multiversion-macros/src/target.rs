@@ -0,0 +1,171 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{
+    parenthesized,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    token, Error, Ident, LitStr, Result, Token,
+};
+
+/// A predicate attached to a [`Target`], gating whether that specialization is generated at all.
+///
+/// Mirrors the shape of rustc's own `cfg` evaluation: leaves test a single condition and
+/// `all`/`any`/`not` combine them. Unlike evaluating these ourselves, [`Predicate::to_cfg_tokens`]
+/// compiles the predicate straight into the token stream of a real `#[cfg(..)]` attribute and
+/// leaves evaluating it to rustc: a proc-macro process has no way to ask "does the crate
+/// currently being compiled have this feature enabled?" or "what's its real `--target`?" itself
+/// (those are only ever reported to build scripts, and a proc-macro crate is always compiled for
+/// the host regardless of what the downstream crate targets), but a `#[cfg(..)]` attached to the
+/// generated code is evaluated by rustc against the actual downstream crate and target, which is
+/// exactly what this predicate is supposed to gate on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Predicate {
+    TargetFeature(String),
+    TargetOs(String),
+    Feature(String),
+    All(Vec<Predicate>),
+    Any(Vec<Predicate>),
+    Not(Box<Predicate>),
+}
+
+impl Predicate {
+    /// Translate this predicate into the inner tokens of a `#[cfg(..)]` attribute, deferring
+    /// evaluation to rustc rather than resolving it here; see the type's docs for why.
+    pub fn to_cfg_tokens(&self) -> TokenStream {
+        match self {
+            Predicate::TargetFeature(feature) => quote! { target_feature = #feature },
+            Predicate::TargetOs(os) => quote! { target_os = #os },
+            Predicate::Feature(name) => quote! { feature = #name },
+            Predicate::All(predicates) => {
+                let inner = predicates.iter().map(Predicate::to_cfg_tokens);
+                quote! { all(#(#inner),*) }
+            }
+            Predicate::Any(predicates) => {
+                let inner = predicates.iter().map(Predicate::to_cfg_tokens);
+                quote! { any(#(#inner),*) }
+            }
+            Predicate::Not(predicate) => {
+                let inner = predicate.to_cfg_tokens();
+                quote! { not(#inner) }
+            }
+        }
+    }
+}
+
+impl Parse for Predicate {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let ident: Ident = input.parse()?;
+        if input.peek(token::Paren) {
+            let content;
+            parenthesized!(content in input);
+            match ident.to_string().as_str() {
+                "all" => Ok(Predicate::All(
+                    Punctuated::<Predicate, Token![,]>::parse_terminated(&content)?
+                        .into_iter()
+                        .collect(),
+                )),
+                "any" => Ok(Predicate::Any(
+                    Punctuated::<Predicate, Token![,]>::parse_terminated(&content)?
+                        .into_iter()
+                        .collect(),
+                )),
+                "not" => {
+                    let predicates =
+                        Punctuated::<Predicate, Token![,]>::parse_terminated(&content)?;
+                    if predicates.len() != 1 {
+                        return Err(Error::new(
+                            ident.span(),
+                            "`not` expects exactly one predicate",
+                        ));
+                    }
+                    Ok(Predicate::Not(Box::new(predicates.into_iter().next().unwrap())))
+                }
+                other => Err(Error::new(
+                    ident.span(),
+                    format!("unknown predicate combinator `{other}`, expected `all`, `any`, or `not`"),
+                )),
+            }
+        } else if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            let value: LitStr = input.parse()?;
+            match ident.to_string().as_str() {
+                "target_feature" => Ok(Predicate::TargetFeature(value.value())),
+                "target_os" => Ok(Predicate::TargetOs(value.value())),
+                other => Err(Error::new(
+                    ident.span(),
+                    format!("unknown predicate key `{other}`, expected `target_feature` or `target_os`"),
+                )),
+            }
+        } else {
+            Ok(Predicate::Feature(ident.to_string()))
+        }
+    }
+}
+
+/// A single `architecture+feature1+feature2` specialization, as written in a `targets` list.
+///
+/// May carry an optional [`Predicate`], written as a trailing `if cfg(..)`, that gates whether
+/// this specialization is generated at all; see [`Target::predicate_cfg`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Target {
+    architecture: Option<String>,
+    features: Vec<String>,
+    predicate: Option<Predicate>,
+}
+
+impl Target {
+    pub fn parse(s: &LitStr) -> Result<Self> {
+        let value = s.value();
+        if value.is_empty() {
+            return Err(Error::new(s.span(), "target must not be empty"));
+        }
+        let mut pieces = value.split('+');
+        let architecture = pieces.next().filter(|s| !s.is_empty()).map(String::from);
+        let features = pieces.map(String::from).collect();
+        Ok(Self {
+            architecture,
+            features,
+            predicate: None,
+        })
+    }
+
+    pub fn has_features_specified(&self) -> bool {
+        !self.features.is_empty()
+    }
+
+    pub fn architecture(&self) -> Option<&str> {
+        self.architecture.as_deref()
+    }
+
+    pub fn features(&self) -> &[String] {
+        &self.features
+    }
+
+    /// The `#[cfg(..)]` predicate this target's clone (and the runtime check that guards calling
+    /// it) must satisfy to exist at all, translated from the attached [`Predicate`] if one was
+    /// given. `None` if the target carries no predicate, i.e. it's unconditionally generated.
+    pub fn predicate_cfg(&self) -> Option<TokenStream> {
+        self.predicate.as_ref().map(Predicate::to_cfg_tokens)
+    }
+}
+
+impl Parse for Target {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let s: LitStr = input.parse()?;
+        let mut target = Target::parse(&s)?;
+        if input.peek(Token![if]) {
+            input.parse::<Token![if]>()?;
+            let cfg_ident: Ident = input.parse()?;
+            if cfg_ident != "cfg" {
+                return Err(Error::new(cfg_ident.span(), "expected `cfg`"));
+            }
+            let content;
+            parenthesized!(content in input);
+            target.predicate = Some(content.parse()?);
+            if !content.is_empty() {
+                return Err(content.error("unexpected tokens after predicate"));
+            }
+        }
+        Ok(target)
+    }
+}